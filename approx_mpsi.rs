@@ -1,8 +1,20 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Instant,
+};
+
 use mpc_bench::{comm::Channels, statistics::Timings, Party, Protocol};
 use rand::{rngs::OsRng, RngCore};
 
+use x25519_dalek::{PublicKey, StaticSecret};
+
 use crate::{
-    secret_sharing::{conditionally_corrupt_share, create_zero_share},
+    dp::randomized_response,
+    secret_sharing::{
+        conditionally_corrupt_share, conditionally_increment_share, create_additive_zero_share,
+        create_zero_share,
+    },
+    secure_channel::{IdentitySecrets, SecureChannels},
     SHARE_BYTE_COUNT,
 };
 use sets_multisets::{
@@ -18,6 +30,15 @@ pub struct ApproximateMpsi {
     hash_count: usize,
     domain_size: usize,
     set_size: usize,
+    preprocess_rounds: usize,
+    // epsilon-local-DP budget for randomized response on the Bloom filter.
+    // `None` disables the mechanism and encodes membership exactly, as
+    // before.
+    epsilon: Option<f64>,
+    // Minimum number of sets an element must appear in to be reported.
+    // `None` keeps the exact present-in-all-sets behavior; `Some(t)`
+    // switches to the additive-counter histogram mode.
+    threshold: Option<usize>,
 }
 
 impl ApproximateMpsi {
@@ -26,12 +47,18 @@ impl ApproximateMpsi {
         hash_count: usize,
         domain_size: usize,
         set_size: usize,
+        preprocess_rounds: usize,
+        epsilon: Option<f64>,
+        threshold: Option<usize>,
     ) -> Self {
         ApproximateMpsi {
             bin_count: (minimum_bin_count).div_ceil(64) * 64,
             hash_count,
             domain_size,
             set_size,
+            preprocess_rounds,
+            epsilon,
+            threshold,
         }
     }
 }
@@ -40,6 +67,17 @@ pub struct ApproximateMpsiParty {
     seeds: Vec<[u8; 16]>,
     bin_count: usize,
     hash_count: usize,
+    preprocess_rounds: usize,
+    epsilon: Option<f64>,
+    // Input-independent zero-shares computed ahead of time by `preprocess`,
+    // consumed one per online query by `run_client_approx`.
+    preprocessed_shares: VecDeque<SimdBytes>,
+    identity: IdentitySecrets,
+    threshold: Option<usize>,
+    // Pairwise seeds for the additive histogram scheme, paired with which
+    // side of the pair this party adds (`true`) or subtracts (`false`).
+    // Only populated (and only used) when `threshold` is `Some`.
+    count_seeds: Vec<([u8; 16], bool)>,
 }
 
 impl Party for ApproximateMpsiParty {
@@ -52,16 +90,33 @@ impl Party for ApproximateMpsiParty {
         n_parties: usize,
         input: &Self::Input,
         channels: &mut Channels,
-        _timings: &mut Timings,
+        timings: &mut Timings,
     ) -> Self::Output {
+        // The histogram (threshold) mode takes the additive-share path in
+        // `run_client_approx`, which never draws from `preprocessed_shares`,
+        // so preprocessing them there would be dead work with a meaningless
+        // "offline" timing. Respect `-p 0` (no offline phase) rather than
+        // forcing at least one round.
+        if id != 0
+            && self.threshold.is_none()
+            && self.preprocess_rounds > 0
+            && self.preprocessed_shares.is_empty()
+        {
+            let offline_start = Instant::now();
+            self.preprocess(self.preprocess_rounds);
+            timings.record("offline", offline_start.elapsed());
+        }
+
+        let mut channels = SecureChannels::new(channels, id, self.identity.clone());
+
         match id {
             0 => {
-                self.run_server_approx(n_parties, channels);
+                self.run_server_approx(n_parties, &mut channels);
                 None
             }
-            1 => Some(self.run_querier_approx(input.as_ref().unwrap(), channels)),
+            1 => Some(self.run_querier_approx(input.as_ref().unwrap(), &mut channels, timings)),
             _ => {
-                self.run_client_approx(input.as_ref().unwrap(), channels);
+                self.run_client_approx(input.as_ref().unwrap(), &mut channels, timings);
                 None
             }
         }
@@ -69,22 +124,36 @@ impl Party for ApproximateMpsiParty {
 }
 
 impl ApproximateMpsiParty {
-    fn run_server_approx(&mut self, n_parties: usize, channels: &mut Channels) {
-        // TODO: The server does not have to aggregate all values, but only those relevant for the query
-        // Receive all clients' shares
-        let mut received_share_iterator = (1..n_parties)
-            .map(|id| SimdBytes::from_bytes(&channels.receive(&id).collect::<Vec<_>>()));
-
-        // Aggregate the clients' shares
-        // TODO: Check that the shares are the correct size?
-        let mut aggregated_share = received_share_iterator.next().unwrap();
-        for received_share in received_share_iterator {
-            aggregated_share ^= received_share;
+    fn run_server_approx(&mut self, n_parties: usize, channels: &mut SecureChannels) {
+        if self.threshold.is_some() {
+            self.run_server_histogram(n_parties, channels);
+            return;
         }
 
+        // TODO: The server does not have to aggregate all values, but only those relevant for the query
+        // Receive every client's share concurrently and XOR each into the
+        // aggregate as soon as it arrives, rather than serializing all
+        // `n_parties - 1` network waits before any aggregation starts. XOR
+        // is commutative, so arrival order doesn't affect the result.
+        let client_ids: Vec<usize> = (1..n_parties).collect();
+        let expected_len = SHARE_BYTE_COUNT * self.bin_count;
+        let aggregated_share: Option<SimdBytes> =
+            channels.receive_all_fold(&client_ids, None, |aggregated, _id, bytes| {
+                assert_eq!(
+                    bytes.len(),
+                    expected_len,
+                    "client share had an unexpected length"
+                );
+                let share = SimdBytes::from_bytes(&bytes);
+                match aggregated {
+                    Some(aggregated) => *aggregated ^= share,
+                    None => *aggregated = Some(share),
+                }
+            });
+        let mut aggregated_share = aggregated_share.expect("at least one client share expected");
+
         // Receive the query patterns from the querying party
-        let query_patterns: Vec<Vec<usize>> =
-            bincode::deserialize(&channels.receive(&1).collect::<Vec<u8>>()).unwrap();
+        let query_patterns: Vec<Vec<usize>> = bincode::deserialize(&channels.receive(&1)).unwrap();
 
         // Identify which shares XOR to 0 and which do not
         let shares: Vec<[u8; 5]> = aggregated_share
@@ -107,10 +176,60 @@ impl ApproximateMpsiParty {
         channels.send(&bincode::serialize(&results).unwrap(), &1);
     }
 
-    fn run_querier_approx(&mut self, input: &Set, channels: &mut Channels) -> Set {
+    /// Histogram variant of `run_server_approx`: instead of an XOR-to-zero
+    /// test (which can only tell whether an element is in *every* set),
+    /// sums each client's additive per-bin counter share to recover, for
+    /// every bin, how many clients had it set. A queried element's count is
+    /// then the minimum over its `hash_count` bins, the standard
+    /// counting-Bloom-filter estimate.
+    fn run_server_histogram(&mut self, n_parties: usize, channels: &mut SecureChannels) {
+        // As in `run_server_approx`, fold each client's counts in as soon
+        // as it arrives instead of waiting for all of them in sequence.
+        // Wrapping addition is commutative, so arrival order is fine.
+        let client_ids: Vec<usize> = (1..n_parties).collect();
+        let aggregated_counts: Option<Vec<u8>> =
+            channels.receive_all_fold(&client_ids, None, |aggregated, _id, counts| {
+                assert_eq!(
+                    counts.len(),
+                    self.bin_count,
+                    "client counts had an unexpected length"
+                );
+                match aggregated {
+                    Some(aggregated) => {
+                        for (accumulated, count) in aggregated.iter_mut().zip(&counts) {
+                            *accumulated = accumulated.wrapping_add(*count);
+                        }
+                    }
+                    None => *aggregated = Some(counts),
+                }
+            });
+        let aggregated_counts = aggregated_counts.expect("at least one client's counts expected");
+
+        let query_patterns: Vec<Vec<usize>> = bincode::deserialize(&channels.receive(&1)).unwrap();
+
+        let results: Vec<u8> = query_patterns
+            .iter()
+            .map(|query_pattern| {
+                query_pattern
+                    .iter()
+                    .map(|&index| aggregated_counts[index])
+                    .min()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        channels.send(&bincode::serialize(&results).unwrap(), &1);
+    }
+
+    fn run_querier_approx(
+        &mut self,
+        input: &Set,
+        channels: &mut SecureChannels,
+        timings: &mut Timings,
+    ) -> Set {
         // TODO: The querier only has to send the relevant bins
         // The first part of the protocol is identical to that of the other clients
-        self.run_client_approx(input, channels);
+        self.run_client_approx(input, channels, timings);
 
         // Send the query patterns to the server
         let elements: Vec<usize> = input.elements.iter().copied().collect();
@@ -121,37 +240,85 @@ impl ApproximateMpsiParty {
         channels.send(&bincode::serialize(&query_patterns).unwrap(), &0);
 
         // Receive the query results from the server
-        let reply = channels.receive(&0);
-        let reply_vec: Vec<u8> = reply.collect();
-        let query_results: Vec<bool> = bincode::deserialize(&reply_vec).unwrap();
-
-        // Find the elements that for which the query result was 1
-        Set::from_iter(
-            elements
-                .iter()
-                .zip(query_results)
-                .filter(|(_, res)| *res)
-                .map(|(element, _)| *element),
-        )
+        let reply_vec = channels.receive(&0);
+
+        if let Some(threshold) = self.threshold {
+            let counts: Vec<u8> = bincode::deserialize(&reply_vec).unwrap();
+            Set::from_iter(
+                elements
+                    .iter()
+                    .zip(counts)
+                    .filter(|(_, count)| *count as usize >= threshold)
+                    .map(|(element, _)| *element),
+            )
+        } else {
+            let query_results: Vec<bool> = bincode::deserialize(&reply_vec).unwrap();
+            Set::from_iter(
+                elements
+                    .iter()
+                    .zip(query_results)
+                    .filter(|(_, res)| *res)
+                    .map(|(element, _)| *element),
+            )
+        }
     }
 
-    fn run_client_approx(&mut self, input: &Set, channels: &mut Channels) {
+    fn run_client_approx(
+        &mut self,
+        input: &Set,
+        channels: &mut SecureChannels,
+        timings: &mut Timings,
+    ) {
+        let online_start = Instant::now();
+
         // Encode the set as a permuted Bloom filter
         let bloom_filter = input.to_bloom_filter(self.bin_count, self.hash_count);
-        let permuted_bloom_filter = bloom_filter;
-
-        // Create a share that is corrupted whenever there is a 1
-        let share = create_zero_share(&self.seeds, SHARE_BYTE_COUNT * self.bin_count);
-        let conditional_share = conditionally_corrupt_share(
-            share,
-            &permuted_bloom_filter
-                .into_iter()
-                .map(|b| !b)
-                .collect::<Vec<_>>(),
-        );
-
-        // Send this party's share to the server
-        channels.send(&conditional_share.to_bytes(), &0);
+        let permuted_bloom_filter = match self.epsilon {
+            Some(epsilon) => randomized_response(&bloom_filter, epsilon),
+            None => bloom_filter,
+        };
+
+        if self.threshold.is_some() {
+            let share = create_additive_zero_share(&self.count_seeds, self.bin_count);
+            let incremented_share = conditionally_increment_share(share, &permuted_bloom_filter);
+            channels.send(&incremented_share, &0);
+        } else {
+            // Take a zero-share computed ahead of time by `preprocess`,
+            // falling back to computing one fresh if preprocessing fell
+            // short.
+            let share = self.next_share();
+            let conditional_share = conditionally_corrupt_share(
+                share,
+                &permuted_bloom_filter
+                    .into_iter()
+                    .map(|b| !b)
+                    .collect::<Vec<_>>(),
+            );
+
+            // Send this party's share to the server
+            channels.send(&conditional_share.to_bytes(), &0);
+        }
+
+        timings.record("online", online_start.elapsed());
+    }
+
+    /// Precomputes `n_rounds` input-independent zero-shares, modeled on the
+    /// Ramen ORAM preprocessing phase: everything here depends only on
+    /// `seeds` and `bin_count`, never on the set being queried, so it can
+    /// run entirely offline, ahead of the query the online phase answers.
+    pub fn preprocess(&mut self, n_rounds: usize) {
+        for _ in 0..n_rounds {
+            self.preprocessed_shares.push_back(create_zero_share(
+                &self.seeds,
+                SHARE_BYTE_COUNT * self.bin_count,
+            ));
+        }
+    }
+
+    fn next_share(&mut self) -> SimdBytes {
+        self.preprocessed_shares
+            .pop_front()
+            .unwrap_or_else(|| create_zero_share(&self.seeds, SHARE_BYTE_COUNT * self.bin_count))
     }
 }
 
@@ -179,12 +346,54 @@ impl Protocol for ApproximateMpsi {
         // Add an empty list of seeds for the server
         party_seeds.insert(0, vec![]);
 
+        // Pairwise seeds for the additive histogram scheme, signed by id
+        // order so the pairwise terms cancel when summed: the lower id
+        // adds its expansion of the shared seed, the higher id subtracts
+        // it. Only used when `self.threshold` is `Some`.
+        let mut count_seeds: Vec<Vec<([u8; 16], bool)>> = vec![vec![]; n_parties];
+        for i in 1..n_parties {
+            for j in (i + 1)..n_parties {
+                let mut seed = [0u8; 16];
+                OsRng.fill_bytes(&mut seed);
+                count_seeds[i].push((seed, true));
+                count_seeds[j].push((seed, false));
+            }
+        }
+
+        // Every party's static identity key and every other party's public
+        // key are generated together here, standing in for an out-of-band
+        // trusted-key distribution: each `IdentitySecrets` only keeps the
+        // keys relevant to its own party.
+        let static_secrets: Vec<StaticSecret> = (0..n_parties)
+            .map(|_| StaticSecret::random_from_rng(OsRng))
+            .collect();
+        let public_keys: Vec<PublicKey> = static_secrets.iter().map(PublicKey::from).collect();
+
+        let mut count_seeds_by_id = count_seeds.into_iter();
+
         party_seeds
             .into_iter()
-            .map(|seeds| ApproximateMpsiParty {
-                seeds,
-                bin_count: self.bin_count,
-                hash_count: self.hash_count,
+            .enumerate()
+            .map(|(id, seeds)| {
+                let trusted_peers = (0..n_parties)
+                    .filter(|&peer_id| peer_id != id)
+                    .map(|peer_id| (peer_id, public_keys[peer_id]))
+                    .collect::<HashMap<_, _>>();
+
+                ApproximateMpsiParty {
+                    seeds,
+                    bin_count: self.bin_count,
+                    hash_count: self.hash_count,
+                    preprocess_rounds: self.preprocess_rounds,
+                    epsilon: self.epsilon,
+                    preprocessed_shares: VecDeque::new(),
+                    identity: IdentitySecrets {
+                        static_secret: static_secrets[id].clone(),
+                        trusted_peers,
+                    },
+                    threshold: self.threshold,
+                    count_seeds: count_seeds_by_id.next().unwrap(),
+                }
             })
             .collect()
     }
@@ -205,17 +414,72 @@ impl Protocol for ApproximateMpsi {
         inputs: &[<Self::Party as Party>::Input],
         outputs: &[<Self::Party as Party>::Output],
     ) -> bool {
-        // Compute the intersection of the input sets
-        let expected_intersection = Set::intersection(
-            &inputs[1..]
-                .iter()
-                .map(|set| set.as_ref().unwrap().clone())
-                .collect::<Vec<_>>(),
-        );
+        let input_sets: Vec<Set> = inputs[1..]
+            .iter()
+            .map(|set| set.as_ref().unwrap().clone())
+            .collect();
 
         // Extract the protocol's output from the querying party (id = 1)
         let actual_intersection = outputs[1].as_ref().unwrap().clone();
 
-        expected_intersection == actual_intersection
+        if let Some(threshold) = self.threshold {
+            // True "in at least `threshold` of the input sets" answer among
+            // the querier's own elements (the only ones it queries),
+            // computed directly rather than via the protocol's own
+            // counting logic.
+            let querier_set = &input_sets[0];
+            let mut counts: HashMap<usize, usize> = HashMap::new();
+            for set in &input_sets {
+                for &element in &set.elements {
+                    *counts.entry(element).or_insert(0) += 1;
+                }
+            }
+            let expected_over_threshold = Set::from_iter(
+                counts
+                    .into_iter()
+                    .filter(|(element, count)| {
+                        *count >= threshold && querier_set.elements.contains(element)
+                    })
+                    .map(|(element, _)| element),
+            );
+            return expected_over_threshold == actual_intersection;
+        }
+
+        // Compute the intersection of the input sets
+        let expected_intersection = Set::intersection(&input_sets);
+
+        match self.epsilon {
+            None => expected_intersection == actual_intersection,
+            Some(epsilon) => {
+                // Randomized response inflates both false positives and
+                // false negatives, so exact set equality is the wrong
+                // success criterion here. Accept a symmetric difference
+                // within the error expected at this epsilon: the
+                // probability that all `hash_count` bins of an element
+                // survive per-bit flips in one party's perturbed filter is
+                // `(1 - p)^hash_count`, and every one of the
+                // `input_sets.len()` contributing parties perturbs
+                // independently, so an intersection element's membership
+                // survives end to end only with probability
+                // `bin_survival_probability^(contributing parties)`. Scale
+                // by the actual intersection size rather than the
+                // configured `set_size`, since that's what can flip here.
+                let flip_probability = 1.0 / (1.0 + epsilon.exp());
+                let bin_survival_probability =
+                    (1.0 - flip_probability).powi(self.hash_count as i32);
+                let survival_probability =
+                    bin_survival_probability.powi(input_sets.len() as i32);
+                let expected_errors =
+                    expected_intersection.elements.len() as f64 * (1.0 - survival_probability);
+                let allowed_errors = (3.0 * expected_errors).ceil() as usize + 1;
+
+                let symmetric_difference = expected_intersection
+                    .elements
+                    .symmetric_difference(&actual_intersection.elements)
+                    .count();
+
+                symmetric_difference <= allowed_errors
+            }
+        }
     }
 }
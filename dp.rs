@@ -0,0 +1,30 @@
+//! Local differential privacy for Bloom-filter membership, via randomized
+//! response (the same flavor of mechanism as Prio's `dp` module, applied
+//! per-bit instead of to an aggregate statistic).
+//!
+//! Each bit of a client's permuted Bloom filter is independently flipped
+//! with probability `p = 1 / (1 + e^epsilon)` before it drives
+//! `conditionally_corrupt_share`, giving every bit epsilon-local
+//! differential privacy. Because the mechanism acts independently across
+//! `bin_count` positions and a queried element touches `hash_count` of
+//! them, the probability that *all* of an element's bins keep their true
+//! value is `(1 - p)^hash_count`; false-positive and false-negative rates
+//! for membership compose from that survival probability, so a smaller
+//! epsilon (more noise) buys privacy at the cost of accuracy.
+
+use rand::{rngs::OsRng, RngCore};
+
+/// Flips each bit of `bits` independently with probability
+/// `p = 1 / (1 + e^epsilon)`.
+pub fn randomized_response(bits: &[bool], epsilon: f64) -> Vec<bool> {
+    let flip_probability = 1.0 / (1.0 + epsilon.exp());
+
+    bits.iter()
+        .map(|&bit| {
+            // Uniform in [0, 1) with 53 bits of precision.
+            let uniform = (OsRng.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+            let flip = uniform < flip_probability;
+            bit ^ flip
+        })
+        .collect()
+}
@@ -7,11 +7,16 @@ use mpc_bench::{comm::FullMesh, Protocol};
 use structopt::StructOpt;
 
 use crate::approx_mpsi::ApproximateMpsi;
+use crate::two_server_mpsi::TwoServerApproximateMpsi;
 
 const SHARE_BYTE_COUNT: usize = 5;
 
 mod approx_mpsi;
+mod dp;
+mod dpf;
 mod secret_sharing;
+mod secure_channel;
+mod two_server_mpsi;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "delegated-mpsi")]
@@ -34,6 +39,26 @@ struct Opt {
     repetitions: usize,
     #[structopt(short = "f", long)]
     results_filename: String,
+    /// Number of zero-shares each client precomputes offline at the start of
+    /// its run, ahead of the online query; that work is timed separately as
+    /// "offline".
+    #[structopt(short = "p", long, default_value = "0")]
+    preprocess_rounds: usize,
+    /// Local-DP budget for the randomized-response Bloom-filter mode. When
+    /// unset, clients encode their exact membership vector as before.
+    #[structopt(short = "e", long)]
+    epsilon: Option<f64>,
+    /// Minimum number of parties an element must be held by to be reported.
+    /// When unset, the protocol reports the exact intersection (present in
+    /// every party's set) as before.
+    #[structopt(short = "t", long)]
+    threshold: Option<usize>,
+    /// Run the two-server oblivious-read variant instead: a second server
+    /// (id 2) also aggregates client shares, and the querier reads bins via
+    /// a DPF instead of sending bin indices in the clear. `-p`/`-e`/`-t` are
+    /// ignored in this mode.
+    #[structopt(long)]
+    two_server: bool,
 }
 
 fn main() {
@@ -46,7 +71,13 @@ fn main() {
         FullMesh::new_with_overhead(Duration::from_secs_f64(opt.latency), opt.bytes_per_sec)
     };
 
-    let stats = ApproximateMpsi::new(opt.bin_count, opt.hash_count, opt.domain_size, opt.set_size)
+    if opt.two_server {
+        let stats = TwoServerApproximateMpsi::new(
+            opt.bin_count,
+            opt.hash_count,
+            opt.domain_size,
+            opt.set_size,
+        )
         .evaluate(
             "Experiment".to_string(),
             opt.party_count,
@@ -54,5 +85,24 @@ fn main() {
             opt.repetitions,
         );
 
-    stats.output_party_csv(1, opt.results_filename.as_str());
+        stats.output_party_csv(1, opt.results_filename.as_str());
+    } else {
+        let stats = ApproximateMpsi::new(
+            opt.bin_count,
+            opt.hash_count,
+            opt.domain_size,
+            opt.set_size,
+            opt.preprocess_rounds,
+            opt.epsilon,
+            opt.threshold,
+        )
+        .evaluate(
+            "Experiment".to_string(),
+            opt.party_count,
+            &network_description,
+            opt.repetitions,
+        );
+
+        stats.output_party_csv(1, opt.results_filename.as_str());
+    }
 }
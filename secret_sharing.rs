@@ -57,11 +57,16 @@ impl BitXorAssign for SimdBytes {
 
 // TODO: Try #[inline]
 pub fn xof(seed: &[u8], byte_count: usize) -> SimdBytes {
+    SimdBytes::from_bytes(&blake3_expand(seed, byte_count))
+}
+
+/// Expands `seed` into `byte_count` pseudorandom bytes using a blake3 XOF.
+/// Shared by [`xof`] and the DPF seed expansion in [`crate::dpf`].
+pub fn blake3_expand(seed: &[u8], byte_count: usize) -> Vec<u8> {
     let mut output_reader = Hasher::new().update(seed).finalize_xof();
     let mut expanded_bytes: Vec<u8> = vec![0; byte_count];
     output_reader.fill(&mut expanded_bytes);
-
-    SimdBytes::from_bytes(&expanded_bytes)
+    expanded_bytes
 }
 
 pub fn create_zero_share(seeds: &[[u8; 16]], byte_count: usize) -> SimdBytes {
@@ -75,6 +80,43 @@ pub fn create_zero_share(seeds: &[[u8; 16]], byte_count: usize) -> SimdBytes {
     share
 }
 
+/// Additive analogue of [`create_zero_share`], one byte per bin instead of
+/// `SHARE_BYTE_COUNT`: used by the histogram mode in `approx_mpsi.rs`,
+/// where the server needs to *sum* clients' per-bin contributions (to
+/// recover a count) rather than XOR them (to recover a present-in-all
+/// test). Each `(seed, is_positive)` pair is the same kind of pairwise
+/// seed as `create_zero_share`, but summed with wrapping addition/
+/// subtraction instead of XOR, so the two parties sharing a seed must
+/// agree on which of them adds and which subtracts for the pairwise terms
+/// to cancel out across all parties.
+pub fn create_additive_zero_share(signed_seeds: &[([u8; 16], bool)], bin_count: usize) -> Vec<u8> {
+    let mut share = vec![0u8; bin_count];
+    for (seed, is_positive) in signed_seeds {
+        let expanded = blake3_expand(seed, bin_count);
+        for (accumulated, random_byte) in share.iter_mut().zip(&expanded) {
+            *accumulated = if *is_positive {
+                accumulated.wrapping_add(*random_byte)
+            } else {
+                accumulated.wrapping_sub(*random_byte)
+            };
+        }
+    }
+    share
+}
+
+/// Additive analogue of [`conditionally_corrupt_share`]: increments (rather
+/// than replaces with randomness) the counter for every bin where
+/// `conditions` is set, so summing every client's share across the server
+/// recovers a per-bin count instead of an XOR-to-zero test.
+pub fn conditionally_increment_share(mut share: Vec<u8>, conditions: &[bool]) -> Vec<u8> {
+    for (counter, &is_set) in share.iter_mut().zip(conditions) {
+        if is_set {
+            *counter = counter.wrapping_add(1);
+        }
+    }
+    share
+}
+
 pub fn conditionally_corrupt_share(share: SimdBytes, conditions: &[bool]) -> SimdBytes {
     let conditions_expanded: Vec<bool> = conditions
         .iter()
@@ -0,0 +1,361 @@
+//! Authenticated, encrypted, auto-rekeying session layer for `Channels`.
+//!
+//! Every `channels.send`/`channels.receive` in `approx_mpsi.rs` moves raw
+//! bincode bytes with no confidentiality, authentication, or replay
+//! protection, so the network model in `main.rs` (`FullMesh`) has to assume
+//! a trusted transport. `SecureChannels` wraps a `Channels` and, on first
+//! contact with a peer, runs a Noise-style handshake: an ephemeral X25519
+//! exchange for forward secrecy, combined with a static X25519 exchange
+//! against a configured set of trusted peer public keys for mutual
+//! authentication, derives a root key from which a pair of per-direction
+//! AEAD keys is KDF'd. Every message after that is sealed with
+//! XChaCha20-Poly1305 under a monotonic nonce; keys are ratcheted forward
+//! via the KDF after a configurable number of messages or bytes, and a
+//! small plaintext header carrying the key epoch and nonce lets the
+//! receiver derive the right key even when messages are lost or reordered.
+//! Since that header is attacker-controllable in transit, each
+//! [`PeerSession`] also tracks every `(epoch, counter)` it has already
+//! accepted from that peer and rejects an exact repeat, so a captured
+//! ciphertext can't be replayed back in verbatim.
+//!
+//! The wrapper is a drop-in replacement for `Channels` from the call
+//! sites' point of view: `conditional_share.to_bytes()` and friends are
+//! still passed to `send`/`receive` unchanged, just sealed underneath.
+//!
+//! [`SecureChannels::receive_all_fold`] additionally lets a party with
+//! many peers (e.g. the server in `approx_mpsi.rs`, aggregating every
+//! client's share) overlap their network waits instead of receiving from
+//! them one at a time.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc;
+use std::thread;
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    XChaCha20Poly1305, XNonce,
+};
+use mpc_bench::comm::Channels;
+use rand::rngs::OsRng;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::secret_sharing::blake3_expand;
+
+/// Ratchet a per-direction key forward after this many messages...
+const REKEY_AFTER_MESSAGES: u64 = 1_000;
+/// ...or after this many plaintext bytes, whichever comes first.
+const REKEY_AFTER_BYTES: u64 = 1 << 24; // 16 MiB
+
+const NONCE_LEN: usize = 24; // XChaCha20Poly1305's extended nonce
+const HEADER_LEN: usize = 4 + 8; // epoch (u32) + counter (u64)
+
+/// A party's long-term identity: its own static keypair, and the peers it
+/// is willing to talk to. Loadable per party (e.g. from a config file) so
+/// the server and every client can mutually authenticate each other.
+/// `Clone` so a `SecureChannels` can own its own copy instead of borrowing
+/// the party's, which would otherwise hold a live borrow of `&self` for as
+/// long as the wrapper exists and conflict with the `&mut self` protocol
+/// methods it's passed into.
+#[derive(Clone)]
+pub struct IdentitySecrets {
+    pub static_secret: StaticSecret,
+    pub trusted_peers: HashMap<usize, PublicKey>,
+}
+
+fn kdf(root_key: &[u8; 32], label: &[u8]) -> [u8; 32] {
+    let mut material = root_key.to_vec();
+    material.extend_from_slice(label);
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&blake3_expand(&material, 32));
+    key
+}
+
+fn ratchet(key: &[u8; 32]) -> [u8; 32] {
+    let mut next = [0u8; 32];
+    next.copy_from_slice(&blake3_expand(key, 32));
+    next
+}
+
+fn encode_nonce(epoch: u32, counter: u64) -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[0..4].copy_from_slice(&epoch.to_le_bytes());
+    nonce[4..12].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
+
+/// Pure (non-caching) counterpart of `PeerSession::receive_key_for_epoch`:
+/// re-derives whichever epoch key `framed` was sealed under by ratcheting
+/// forward from `base_key` from scratch every time, rather than reading
+/// from a session-wide cache. Used by `receive_all_fold`, where each
+/// peer's message is decrypted on its own worker thread and there is no
+/// single `&mut PeerSession` to share a cache through. Returns the header's
+/// `(epoch, counter)` alongside the plaintext so the caller can run them
+/// through `PeerSession::check_replay` back on the thread that owns the
+/// session, since decryption itself runs on the worker thread.
+fn decrypt_with_base_key(base_key: [u8; 32], framed: &[u8]) -> (u32, u64, Vec<u8>) {
+    let epoch = u32::from_le_bytes(framed[0..4].try_into().unwrap());
+    let nonce_counter = u64::from_le_bytes(framed[4..12].try_into().unwrap());
+    let ciphertext = &framed[HEADER_LEN..];
+
+    let mut key = base_key;
+    for _ in 0..epoch {
+        key = ratchet(&key);
+    }
+
+    let cipher = XChaCha20Poly1305::new((&key).into());
+    let nonce = encode_nonce(epoch, nonce_counter);
+    let plaintext = cipher
+        .decrypt(XNonce::from_slice(&nonce), ciphertext)
+        .expect("authentication failed: message was tampered with, forged, or misrouted");
+    (epoch, nonce_counter, plaintext)
+}
+
+struct DirectionKey {
+    key: [u8; 32],
+    epoch: u32,
+    nonce_counter: u64,
+    messages_since_rekey: u64,
+    bytes_since_rekey: u64,
+}
+
+impl DirectionKey {
+    fn new(key: [u8; 32]) -> Self {
+        DirectionKey {
+            key,
+            epoch: 0,
+            nonce_counter: 0,
+            messages_since_rekey: 0,
+            bytes_since_rekey: 0,
+        }
+    }
+
+    fn ratchet_if_due(&mut self) {
+        if self.messages_since_rekey >= REKEY_AFTER_MESSAGES
+            || self.bytes_since_rekey >= REKEY_AFTER_BYTES
+        {
+            self.key = ratchet(&self.key);
+            self.epoch += 1;
+            self.nonce_counter = 0;
+            self.messages_since_rekey = 0;
+            self.bytes_since_rekey = 0;
+        }
+    }
+}
+
+struct PeerSession {
+    send: DirectionKey,
+    receive_base_key: [u8; 32],
+    receive_keys_by_epoch: HashMap<u32, [u8; 32]>,
+    // Every (epoch, counter) already accepted from this peer. The header
+    // that carries the epoch/counter is plaintext and attacker-controllable
+    // in transit, so authentication alone doesn't stop a captured
+    // ciphertext from being replayed verbatim; rejecting a repeat here does.
+    seen_nonces: HashSet<(u32, u64)>,
+}
+
+impl PeerSession {
+    /// Derives (and caches) the receive key for `epoch` by ratcheting
+    /// forward from the post-handshake key. Out-of-order delivery across
+    /// an epoch boundary is fine: the header says which epoch a message
+    /// was sealed under, so the receiver can always derive that key.
+    fn receive_key_for_epoch(&mut self, epoch: u32) -> [u8; 32] {
+        if epoch == 0 {
+            return self.receive_base_key;
+        }
+        if let Some(key) = self.receive_keys_by_epoch.get(&epoch) {
+            return *key;
+        }
+        let previous = self.receive_key_for_epoch(epoch - 1);
+        let key = ratchet(&previous);
+        self.receive_keys_by_epoch.insert(epoch, key);
+        key
+    }
+
+    /// Rejects a nonce this peer has already used. Reordering and gaps
+    /// within or across epochs are still fine (that's the whole point of
+    /// keying by the header's epoch/counter rather than a strict sequence
+    /// number); only an exact repeat is a replay.
+    fn check_replay(&mut self, epoch: u32, counter: u64) {
+        assert!(
+            self.seen_nonces.insert((epoch, counter)),
+            "replay detected: nonce (epoch {epoch}, counter {counter}) was already used by this peer"
+        );
+    }
+}
+
+/// A `Channels`-like wrapper that authenticates and encrypts every message
+/// to/from each peer, handshaking lazily on first contact.
+pub struct SecureChannels<'a> {
+    inner: &'a mut Channels,
+    own_id: usize,
+    identity: IdentitySecrets,
+    sessions: HashMap<usize, PeerSession>,
+}
+
+impl<'a> SecureChannels<'a> {
+    pub fn new(inner: &'a mut Channels, own_id: usize, identity: IdentitySecrets) -> Self {
+        SecureChannels {
+            inner,
+            own_id,
+            identity,
+            sessions: HashMap::new(),
+        }
+    }
+
+    fn session(&mut self, peer_id: usize) -> &mut PeerSession {
+        if !self.sessions.contains_key(&peer_id) {
+            let session = self.handshake(peer_id);
+            self.sessions.insert(peer_id, session);
+        }
+        self.sessions.get_mut(&peer_id).unwrap()
+    }
+
+    /// Two-message Noise-style handshake: both sides send an ephemeral
+    /// public key, then combine the ephemeral DH with a static DH against
+    /// the peer's trusted public key, so the derived root key is both
+    /// forward-secret and authenticated.
+    fn handshake(&mut self, peer_id: usize) -> PeerSession {
+        let trusted_peer_key = *self
+            .identity
+            .trusted_peers
+            .get(&peer_id)
+            .expect("peer is not in the trusted key set");
+
+        let ephemeral_secret = StaticSecret::random_from_rng(OsRng);
+        let ephemeral_public = PublicKey::from(&ephemeral_secret);
+        self.inner.send(ephemeral_public.as_bytes(), &peer_id);
+
+        let their_ephemeral_bytes: Vec<u8> = self.inner.receive(&peer_id).collect();
+        let mut their_ephemeral_array = [0u8; 32];
+        their_ephemeral_array.copy_from_slice(&their_ephemeral_bytes);
+        let their_ephemeral_public = PublicKey::from(their_ephemeral_array);
+
+        let ephemeral_shared = ephemeral_secret.diffie_hellman(&their_ephemeral_public);
+        let static_shared = self
+            .identity
+            .static_secret
+            .diffie_hellman(&trusted_peer_key);
+
+        let transcript = [ephemeral_shared.as_bytes(), static_shared.as_bytes()].concat();
+        let mut root_key = [0u8; 32];
+        root_key.copy_from_slice(&blake3_expand(&transcript, 32));
+
+        // Direction labels are asymmetric in id order so both sides agree
+        // on which derived key encrypts which direction without a separate
+        // negotiation round.
+        let (send_label, receive_label): (&[u8], &[u8]) = if self.own_id < peer_id {
+            (b"lo->hi", b"hi->lo")
+        } else {
+            (b"hi->lo", b"lo->hi")
+        };
+
+        PeerSession {
+            send: DirectionKey::new(kdf(&root_key, send_label)),
+            receive_base_key: kdf(&root_key, receive_label),
+            receive_keys_by_epoch: HashMap::new(),
+            seen_nonces: HashSet::new(),
+        }
+    }
+
+    pub fn send(&mut self, plaintext: &[u8], peer_id: &usize) {
+        let peer_id = *peer_id;
+        let plaintext_len = plaintext.len() as u64;
+        let session = self.session(peer_id);
+        session.send.ratchet_if_due();
+
+        let (epoch, nonce_counter) = (session.send.epoch, session.send.nonce_counter);
+        session.send.nonce_counter += 1;
+        session.send.messages_since_rekey += 1;
+        session.send.bytes_since_rekey += plaintext_len;
+
+        let cipher = XChaCha20Poly1305::new((&session.send.key).into());
+        let nonce = encode_nonce(epoch, nonce_counter);
+        let ciphertext = cipher
+            .encrypt(XNonce::from_slice(&nonce), plaintext)
+            .expect("encryption should not fail for a well-formed key/nonce");
+
+        let mut framed = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+        framed.extend_from_slice(&epoch.to_le_bytes());
+        framed.extend_from_slice(&nonce_counter.to_le_bytes());
+        framed.extend_from_slice(&ciphertext);
+
+        self.inner.send(&framed, &peer_id);
+    }
+
+    pub fn receive(&mut self, peer_id: &usize) -> Vec<u8> {
+        let peer_id = *peer_id;
+        // Handshake first on first contact, so the peer's handshake message
+        // is not read as a sealed frame.
+        self.session(peer_id);
+        let framed: Vec<u8> = self.inner.receive(&peer_id).collect();
+        let epoch = u32::from_le_bytes(framed[0..4].try_into().unwrap());
+        let nonce_counter = u64::from_le_bytes(framed[4..12].try_into().unwrap());
+        let ciphertext = &framed[HEADER_LEN..];
+
+        let session = self.session(peer_id);
+        let key = session.receive_key_for_epoch(epoch);
+
+        let cipher = XChaCha20Poly1305::new((&key).into());
+        let nonce = encode_nonce(epoch, nonce_counter);
+        let plaintext = cipher
+            .decrypt(XNonce::from_slice(&nonce), ciphertext)
+            .expect("authentication failed: message was tampered with, forged, or misrouted");
+
+        self.session(peer_id).check_replay(epoch, nonce_counter);
+        plaintext
+    }
+
+    /// Receives one message from every id in `peer_ids`, calling `fold` as
+    /// each one arrives rather than waiting for all of them sequentially
+    /// like a `1..n_parties` loop over `receive` would. Each peer's
+    /// network wait runs on its own thread (`Channels` is assumed to be a
+    /// cheap, shareable handle onto the underlying simulated transport, as
+    /// these benchmark-harness channel types typically are), so a slow
+    /// peer no longer blocks every other peer's wait — this is the
+    /// thread-based analogue of a `FuturesUnordered` completion set in a
+    /// codebase with no async runtime. `fold` is called on the calling
+    /// thread in arrival order, which is why it is safe for it to be
+    /// order-dependent in everything except the actual aggregation (e.g.
+    /// XOR, wrapping addition) it performs.
+    ///
+    /// Any peer without a session is handshaken with first, sequentially
+    /// and before any receive starts, since the handshake itself needs
+    /// `&mut self` and cannot run concurrently across peers.
+    pub fn receive_all_fold<T>(
+        &mut self,
+        peer_ids: &[usize],
+        mut initial: T,
+        mut fold: impl FnMut(&mut T, usize, Vec<u8>),
+    ) -> T {
+        let base_keys: Vec<(usize, [u8; 32])> = peer_ids
+            .iter()
+            .map(|&peer_id| (peer_id, self.session(peer_id).receive_base_key))
+            .collect();
+
+        let (tx, rx) = mpsc::channel();
+        thread::scope(|scope| {
+            for (peer_id, base_key) in base_keys {
+                let tx = tx.clone();
+                let mut inner = self.inner.clone();
+                scope.spawn(move || {
+                    let framed: Vec<u8> = inner.receive(&peer_id).collect();
+                    let (epoch, nonce_counter, plaintext) =
+                        decrypt_with_base_key(base_key, &framed);
+                    tx.send((peer_id, epoch, nonce_counter, plaintext))
+                        .expect("receiver outlives every sender thread");
+                });
+            }
+            drop(tx);
+
+            // Decryption ran off-thread (no single `&mut PeerSession` to
+            // share a replay cache through there), so the replay check runs
+            // here instead, back on the thread that owns `self`.
+            for (peer_id, epoch, nonce_counter, plaintext) in rx {
+                self.session(peer_id).check_replay(epoch, nonce_counter);
+                fold(&mut initial, peer_id, plaintext);
+            }
+        });
+
+        initial
+    }
+}
@@ -0,0 +1,251 @@
+//! Two-server variant of [`crate::approx_mpsi`] that hides the querier's
+//! queried elements from the server(s) doing the aggregation.
+//!
+//! In the single-server protocol the querier sends `bloom_filter_indices`
+//! for every element it holds to the server in the clear (see
+//! `run_querier_approx`), so the server learns exactly which bins are being
+//! probed. Here a second, non-colluding server (id [`SERVER_B_ID`]) also
+//! aggregates the clients' shares, and the querier reads each relevant bin
+//! from both servers via a [`crate::dpf`] point function instead of
+//! revealing the bin index: each server only ever sees one half of a DPF
+//! key, which on its own looks like a uniformly random GGM path and reveals
+//! nothing about which bin is being read.
+
+use std::collections::HashMap;
+
+use mpc_bench::{comm::Channels, statistics::Timings, Party, Protocol};
+use rand::{rngs::OsRng, RngCore};
+
+use crate::{
+    dpf::{self, DpfKey},
+    secret_sharing::{conditionally_corrupt_share, create_zero_share, SimdBytes},
+    SHARE_BYTE_COUNT,
+};
+use sets_multisets::{
+    bloom_filters::bloom_filter_indices,
+    sets::{gen_sets_with_uniform_intersection, Set},
+};
+
+/// Id of the second aggregating server. Id `0` remains the first server, id
+/// `1` remains the querier, and every other id is a plain client.
+const SERVER_B_ID: usize = 2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TwoServerApproximateMpsi {
+    bin_count: usize,
+    hash_count: usize,
+    domain_size: usize,
+    set_size: usize,
+}
+
+impl TwoServerApproximateMpsi {
+    /// `minimum_bin_count` is rounded up to the next power of two: each bin
+    /// index is the point a DPF is generated for, and the GGM-tree
+    /// construction needs a domain of size `2^domain_bits`.
+    pub fn new(
+        minimum_bin_count: usize,
+        hash_count: usize,
+        domain_size: usize,
+        set_size: usize,
+    ) -> Self {
+        TwoServerApproximateMpsi {
+            bin_count: minimum_bin_count.next_power_of_two(),
+            hash_count,
+            domain_size,
+            set_size,
+        }
+    }
+}
+
+pub struct TwoServerApproximateMpsiParty {
+    seeds: Vec<[u8; 16]>,
+    bin_count: usize,
+    hash_count: usize,
+}
+
+impl Party for TwoServerApproximateMpsiParty {
+    type Input = Option<Set>;
+    type Output = Option<Set>;
+
+    fn run(
+        &mut self,
+        id: usize,
+        n_parties: usize,
+        input: &Self::Input,
+        channels: &mut Channels,
+        _timings: &mut Timings,
+    ) -> Self::Output {
+        match id {
+            0 | SERVER_B_ID => {
+                self.run_server_two_server(n_parties, channels);
+                None
+            }
+            1 => Some(self.run_querier_two_server(input.as_ref().unwrap(), channels)),
+            _ => {
+                self.run_client_two_server(input.as_ref().unwrap(), channels);
+                None
+            }
+        }
+    }
+}
+
+impl TwoServerApproximateMpsiParty {
+    /// Shared by both server ids: aggregate the clients' shares into a full
+    /// copy of the XOR-to-zero database, then answer the querier's DPF
+    /// reads against it. Each server only ever sees its own half of each
+    /// DPF key pair, so neither can tell which bin a given reply is for.
+    fn run_server_two_server(&mut self, n_parties: usize, channels: &mut Channels) {
+        let client_ids = (1..n_parties).filter(|&id| id != SERVER_B_ID);
+        let mut received_shares =
+            client_ids.map(|id| SimdBytes::from_bytes(&channels.receive(&id).collect::<Vec<_>>()));
+
+        let mut aggregated_share = received_shares.next().unwrap();
+        for share in received_shares {
+            aggregated_share ^= share;
+        }
+        let database: Vec<[u8; SHARE_BYTE_COUNT]> = aggregated_share
+            .to_bytes()
+            .array_chunks::<SHARE_BYTE_COUNT>()
+            .copied()
+            .collect();
+
+        // One DPF key per (element, hash index) the querier wants read.
+        let keys: Vec<Vec<DpfKey>> =
+            bincode::deserialize(&channels.receive(&1).collect::<Vec<u8>>()).unwrap();
+
+        let results: Vec<[u8; SHARE_BYTE_COUNT]> = keys
+            .iter()
+            .map(|element_keys| {
+                let mut partial = [0u8; SHARE_BYTE_COUNT];
+                for key in element_keys {
+                    for (bin, entry) in database.iter().enumerate() {
+                        if key.eval(bin) == 1 {
+                            for (p, e) in partial.iter_mut().zip(entry) {
+                                *p ^= *e;
+                            }
+                        }
+                    }
+                }
+                partial
+            })
+            .collect();
+
+        channels.send(&bincode::serialize(&results).unwrap(), &1);
+    }
+
+    fn run_querier_two_server(&mut self, input: &Set, channels: &mut Channels) -> Set {
+        // The querier is also a client: its own share must be folded into
+        // the aggregate like everyone else's.
+        self.run_client_two_server(input, channels);
+
+        let elements: Vec<usize> = input.elements.iter().copied().collect();
+        let domain_bits = self.bin_count.trailing_zeros() as usize;
+
+        let mut keys_for_a = Vec::with_capacity(elements.len());
+        let mut keys_for_b = Vec::with_capacity(elements.len());
+        for element in &elements {
+            let mut a_keys = Vec::with_capacity(self.hash_count);
+            let mut b_keys = Vec::with_capacity(self.hash_count);
+            for index in bloom_filter_indices(*element, self.bin_count, self.hash_count) {
+                let (key_a, key_b) = dpf::gen(index, domain_bits);
+                a_keys.push(key_a);
+                b_keys.push(key_b);
+            }
+            keys_for_a.push(a_keys);
+            keys_for_b.push(b_keys);
+        }
+
+        channels.send(&bincode::serialize(&keys_for_a).unwrap(), &0);
+        channels.send(&bincode::serialize(&keys_for_b).unwrap(), &SERVER_B_ID);
+
+        let reply_a: Vec<[u8; SHARE_BYTE_COUNT]> =
+            bincode::deserialize(&channels.receive(&0).collect::<Vec<u8>>()).unwrap();
+        let reply_b: Vec<[u8; SHARE_BYTE_COUNT]> =
+            bincode::deserialize(&channels.receive(&SERVER_B_ID).collect::<Vec<u8>>()).unwrap();
+
+        // Recombining the two servers' replies recovers the same
+        // XOR-to-zero test `run_server_approx` does in one step.
+        Set::from_iter(
+            elements
+                .iter()
+                .zip(reply_a.iter().zip(&reply_b))
+                .filter_map(|(element, (share_a, share_b))| {
+                    let mut xor = [0u8; SHARE_BYTE_COUNT];
+                    for ((x, y), z) in xor.iter_mut().zip(share_a).zip(share_b) {
+                        *x = *y ^ *z;
+                    }
+                    (xor == [0u8; SHARE_BYTE_COUNT]).then_some(*element)
+                }),
+        )
+    }
+
+    fn run_client_two_server(&mut self, input: &Set, channels: &mut Channels) {
+        let bloom_filter = input.to_bloom_filter(self.bin_count, self.hash_count);
+
+        let share = create_zero_share(&self.seeds, SHARE_BYTE_COUNT * self.bin_count);
+        let conditional_share = conditionally_corrupt_share(
+            share,
+            &bloom_filter.into_iter().map(|b| !b).collect::<Vec<_>>(),
+        );
+
+        let bytes = conditional_share.to_bytes();
+        channels.send(&bytes, &0);
+        channels.send(&bytes, &SERVER_B_ID);
+    }
+}
+
+impl Protocol for TwoServerApproximateMpsi {
+    type Party = TwoServerApproximateMpsiParty;
+
+    fn setup_parties(&self, n_parties: usize) -> Vec<Self::Party> {
+        // Every id except the two servers (0 and SERVER_B_ID) takes part in
+        // the pairwise-seed zero-sharing scheme.
+        let client_ids: Vec<usize> = (1..n_parties).filter(|&id| id != SERVER_B_ID).collect();
+        let mut seeds_by_id: HashMap<usize, Vec<[u8; 16]>> =
+            client_ids.iter().map(|&id| (id, vec![])).collect();
+        for (position, &id_a) in client_ids.iter().enumerate() {
+            for &id_b in &client_ids[(position + 1)..] {
+                let mut seed = [0u8; 16];
+                OsRng.fill_bytes(&mut seed);
+                seeds_by_id.get_mut(&id_a).unwrap().push(seed);
+                seeds_by_id.get_mut(&id_b).unwrap().push(seed);
+            }
+        }
+
+        (0..n_parties)
+            .map(|id| TwoServerApproximateMpsiParty {
+                seeds: seeds_by_id.remove(&id).unwrap_or_default(),
+                bin_count: self.bin_count,
+                hash_count: self.hash_count,
+            })
+            .collect()
+    }
+
+    fn generate_inputs(&self, n_parties: usize) -> Vec<<Self::Party as Party>::Input> {
+        let mut sets =
+            gen_sets_with_uniform_intersection(n_parties - 2, self.set_size, self.domain_size)
+                .into_iter()
+                .map(Some);
+
+        let mut inputs = vec![None, sets.next().unwrap(), None];
+        inputs.extend(sets);
+        inputs
+    }
+
+    fn validate_outputs(
+        &self,
+        inputs: &[<Self::Party as Party>::Input],
+        outputs: &[<Self::Party as Party>::Output],
+    ) -> bool {
+        let expected_intersection = Set::intersection(
+            &inputs
+                .iter()
+                .filter_map(|set| set.clone())
+                .collect::<Vec<_>>(),
+        );
+
+        let actual_intersection = outputs[1].as_ref().unwrap().clone();
+
+        expected_intersection == actual_intersection
+    }
+}